@@ -0,0 +1,160 @@
+//! Delivery acknowledgement for IRC lines
+//!
+//! `send_privmsg` only queues a line in the IRC client's in-memory buffer, so
+//! a successful send says nothing about whether the server received it. Once
+//! the queue is idle, and at least every `BARRIER_EVERY_LINES` lines or
+//! `BARRIER_INTERVAL` under steady load, the sender queues `PING :ack-<seq>`.
+//! The server answers PINGs in order, so the matching PONG proves every line
+//! sent before it arrived. Only then are lines marked delivered in the
+//! journal. Lines still waiting for their PONG when the connection drops are
+//! sent again after reconnecting.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Distinguishes our PING tokens from the client's keepalive timestamps
+const TOKEN_PREFIX: &str = "ack-";
+
+/// Request an acknowledgement at least every this many lines...
+pub const BARRIER_EVERY_LINES: usize = 10;
+
+/// ...or this long after the previous one, even if the queue never goes idle
+pub const BARRIER_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A queued line handed to the IRC client but not yet acknowledged
+pub struct SentLine {
+    seq: u64,
+    /// Journal id, if the queue is persisted to disk
+    pub journal_id: Option<u64>,
+    /// The lines as sent (stamped, after de-duplication); may be empty if the
+    /// line was folded into a repeat count
+    pub texts: Vec<String>,
+}
+
+pub struct AckTracker {
+    next_seq: u64,
+    sent: VecDeque<SentLine>,
+    /// Lines sent since the last barrier
+    unbarriered: usize,
+    last_barrier: Instant,
+}
+
+impl AckTracker {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            next_seq: 0,
+            sent: VecDeque::new(),
+            unbarriered: 0,
+            last_barrier: now,
+        }
+    }
+
+    /// Record a line handed to the client
+    pub fn sent(&mut self, journal_id: Option<u64>, texts: Vec<String>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.unbarriered += 1;
+        self.sent.push_back(SentLine {
+            seq,
+            journal_id,
+            texts,
+        });
+    }
+
+    /// Whether enough has been sent since the last barrier to request one
+    /// without waiting for the queue to go idle
+    pub fn barrier_due(&self, now: Instant) -> bool {
+        self.unbarriered >= BARRIER_EVERY_LINES
+            || (self.unbarriered > 0
+                && now.saturating_duration_since(self.last_barrier) >= BARRIER_INTERVAL)
+    }
+
+    /// PING token acknowledging everything sent so far, if anything is waiting
+    pub fn barrier(&mut self, now: Instant) -> Option<String> {
+        self.unbarriered = 0;
+        self.last_barrier = now;
+        self.sent
+            .back()
+            .map(|line| format!("{}{}", TOKEN_PREFIX, line.seq))
+    }
+
+    /// Handle a PONG token, returning the journal ids it acknowledges.
+    /// Tokens that aren't ours (e.g. the client's keepalive PINGs) are ignored.
+    pub fn acknowledge(&mut self, token: &str) -> Vec<u64> {
+        let Some(Ok(acked)) = token.strip_prefix(TOKEN_PREFIX).map(str::parse::<u64>) else {
+            return Vec::new();
+        };
+        let mut ids = Vec::new();
+        while self.sent.front().is_some_and(|line| line.seq <= acked) {
+            ids.extend(self.sent.pop_front().and_then(|line| line.journal_id));
+        }
+        ids
+    }
+
+    /// Acknowledge everything, e.g. once the server closed the connection in
+    /// response to our QUIT
+    pub fn acknowledge_all(&mut self) -> Vec<u64> {
+        self.sent
+            .drain(..)
+            .filter_map(|line| line.journal_id)
+            .collect()
+    }
+
+    /// Take the unacknowledged lines, oldest first, to send again after a
+    /// reconnect
+    pub fn take_unacked(&mut self) -> Vec<SentLine> {
+        self.sent.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pong_acknowledges_lines_sent_before_its_ping() {
+        let mut acks = AckTracker::new(Instant::now());
+        acks.sent(Some(10), vec!["[INFO] one".into()]);
+        acks.sent(None, vec!["[INFO] two".into()]);
+        let barrier = acks.barrier(Instant::now()).unwrap();
+        acks.sent(Some(12), vec!["[INFO] three".into()]);
+
+        assert_eq!(acks.acknowledge(&barrier), vec![10]);
+        // Keepalive PONGs from the client itself are not ours
+        assert!(acks.acknowledge("1700000000").is_empty());
+
+        let unacked = acks.take_unacked();
+        assert_eq!(unacked.len(), 1);
+        assert_eq!(unacked[0].texts, vec!["[INFO] three"]);
+        assert_eq!(acks.barrier(Instant::now()), None);
+    }
+
+    #[test]
+    fn barrier_is_due_under_steady_load() {
+        let t0 = Instant::now();
+        let mut acks = AckTracker::new(t0);
+        assert!(!acks.barrier_due(t0 + BARRIER_INTERVAL));
+
+        for _ in 0..BARRIER_EVERY_LINES - 1 {
+            acks.sent(None, vec!["[INFO] busy".into()]);
+        }
+        assert!(!acks.barrier_due(t0));
+        // Due by time with only a few lines waiting...
+        assert!(acks.barrier_due(t0 + BARRIER_INTERVAL));
+        // ...or by count
+        acks.sent(None, vec!["[INFO] busy".into()]);
+        assert!(acks.barrier_due(t0));
+
+        acks.barrier(t0);
+        assert!(!acks.barrier_due(t0));
+    }
+
+    #[test]
+    fn acknowledge_all_returns_every_journal_id() {
+        let mut acks = AckTracker::new(Instant::now());
+        acks.sent(Some(1), Vec::new());
+        acks.sent(Some(2), vec!["[WARN] x".into()]);
+        assert_eq!(acks.acknowledge_all(), vec![1, 2]);
+        assert!(acks.acknowledge_all().is_empty());
+    }
+}
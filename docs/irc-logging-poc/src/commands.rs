@@ -0,0 +1,319 @@
+//! IRC bot commands
+//!
+//! Lets users in the log channel query the running service:
+//!   !help       list commands
+//!   !supported  networks this instance serves
+//!   !status     uptime and last settlement (privileged)
+//!   !health     provider health summary (privileged)
+//!
+//! Privileged commands are only answered for nicks listed in `IRC_ADMIN_NICKS`.
+//! Replies are throttled per nick and globally so nobody can make the bot
+//! flood itself off the server.
+
+use once_cell::sync::Lazy;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Service status reported through bot commands and the heartbeat, updated by
+/// the application
+pub static STATUS: Lazy<ServiceStatus> = Lazy::new(ServiceStatus::new);
+
+pub struct ServiceStatus {
+    started_at: Instant,
+    inner: RwLock<StatusInner>,
+}
+
+#[derive(Default)]
+struct StatusInner {
+    requests: u64,
+    settlements_ok: u64,
+    settlements_failed: u64,
+    last_settlement: Option<(Instant, String)>,
+    providers: BTreeMap<String, bool>,
+    supported: Vec<String>,
+}
+
+impl ServiceStatus {
+    fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+            inner: RwLock::new(StatusInner::default()),
+        }
+    }
+
+    /// Count a handled request
+    pub fn record_request(&self) {
+        self.write().requests += 1;
+    }
+
+    /// Record a successful settlement (e.g. tx hash and network)
+    pub fn record_settlement(&self, summary: impl Into<String>) {
+        let mut inner = self.write();
+        inner.settlements_ok += 1;
+        inner.last_settlement = Some((Instant::now(), summary.into()));
+    }
+
+    /// Record a failed settlement
+    pub fn record_settlement_failure(&self) {
+        self.write().settlements_failed += 1;
+    }
+
+    /// Total requests handled so far
+    pub fn requests(&self) -> u64 {
+        self.read().requests
+    }
+
+    /// One-line summary for the periodic heartbeat. `requests_in_interval` is
+    /// the number of requests handled since the previous heartbeat.
+    pub fn heartbeat_summary(&self, requests_in_interval: u64, interval: Duration) -> String {
+        let inner = self.read();
+        let per_min = requests_in_interval as f64 * 60.0 / interval.as_secs_f64().max(1.0);
+        let settled = inner.settlements_ok + inner.settlements_failed;
+        let success = if settled == 0 {
+            "n/a".to_string()
+        } else {
+            format!(
+                "{}/{} ({:.0}%)",
+                inner.settlements_ok,
+                settled,
+                inner.settlements_ok as f64 * 100.0 / settled as f64
+            )
+        };
+        format!(
+            "Heartbeat: {:.1} req/min | Settle success: {} | {}",
+            per_min,
+            success,
+            provider_summary(&inner)
+        )
+    }
+
+    /// Update the health of a named RPC provider
+    pub fn set_provider_health(&self, provider: impl Into<String>, healthy: bool) {
+        self.write().providers.insert(provider.into(), healthy);
+    }
+
+    /// Set the networks this instance serves
+    pub fn set_supported(&self, networks: Vec<String>) {
+        self.write().supported = networks;
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, StatusInner> {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, StatusInner> {
+        self.inner.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Answer a channel message if it is a bot command, returning the reply
+pub fn handle_command(
+    status: &ServiceStatus,
+    nick: Option<&str>,
+    text: &str,
+    admins: &[String],
+) -> Option<String> {
+    let command = text.split_whitespace().next()?;
+    if !command.starts_with('!') {
+        return None;
+    }
+
+    let authorized = nick.is_some_and(|nick| admins.iter().any(|a| a.eq_ignore_ascii_case(nick)));
+
+    let reply = match command {
+        "!help" => "Commands: !status, !health, !supported".to_string(),
+        "!supported" => {
+            let inner = status.read();
+            if inner.supported.is_empty() {
+                "Supported networks: none configured".to_string()
+            } else {
+                format!("Supported networks: {}", inner.supported.join(", "))
+            }
+        }
+        "!status" | "!health" if !authorized => {
+            format!("{}: not authorized", nick.unwrap_or("unknown"))
+        }
+        "!status" => {
+            let inner = status.read();
+            let last = match &inner.last_settlement {
+                Some((at, summary)) => {
+                    format!("{} ({} ago)", summary, format_duration(at.elapsed()))
+                }
+                None => "none".to_string(),
+            };
+            format!(
+                "Uptime: {} | Last settlement: {}",
+                format_duration(status.started_at.elapsed()),
+                last
+            )
+        }
+        "!health" => provider_summary(&status.read()),
+        _ => return None,
+    };
+
+    Some(reply)
+}
+
+/// Minimum time between answered commands from the same nick
+pub const NICK_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Minimum time between any two answered commands
+pub const GLOBAL_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// Decides which commands get a reply; the rest are silently ignored
+pub struct Cooldown {
+    per_nick: Duration,
+    global: Duration,
+    last_reply: Option<Instant>,
+    /// Last answered command per (lowercased) nick, within `per_nick`
+    by_nick: HashMap<String, Instant>,
+}
+
+impl Cooldown {
+    pub fn new(per_nick: Duration, global: Duration) -> Self {
+        Self {
+            per_nick,
+            global,
+            last_reply: None,
+            by_nick: HashMap::new(),
+        }
+    }
+
+    /// Whether a command from `nick` may be answered at `now`. An allowed
+    /// command starts both cooldowns.
+    pub fn allow(&mut self, nick: Option<&str>, now: Instant) -> bool {
+        let per_nick = self.per_nick;
+        self.by_nick
+            .retain(|_, at| now.saturating_duration_since(*at) < per_nick);
+
+        let nick = nick.unwrap_or("").to_lowercase();
+        let global_ok = self
+            .last_reply
+            .is_none_or(|at| now.saturating_duration_since(at) >= self.global);
+        if !global_ok || self.by_nick.contains_key(&nick) {
+            return false;
+        }
+
+        self.last_reply = Some(now);
+        self.by_nick.insert(nick, now);
+        true
+    }
+}
+
+/// e.g. "Providers 1/2 healthy: avalanche=DOWN, base=ok"
+fn provider_summary(inner: &StatusInner) -> String {
+    if inner.providers.is_empty() {
+        return "Providers: no data yet".to_string();
+    }
+    let healthy = inner.providers.values().filter(|h| **h).count();
+    let summary: Vec<String> = inner
+        .providers
+        .iter()
+        .map(|(name, ok)| format!("{}={}", name, if *ok { "ok" } else { "DOWN" }))
+        .collect();
+    format!(
+        "Providers {}/{} healthy: {}",
+        healthy,
+        inner.providers.len(),
+        summary.join(", ")
+    )
+}
+
+/// Format a duration as e.g. "1h 2m 3s"
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (h, m, s) = (secs / 3600, (secs % 3600) / 60, secs % 60);
+    if h > 0 {
+        format!("{}h {}m {}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m {}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admins() -> Vec<String> {
+        vec!["ops".to_string()]
+    }
+
+    #[test]
+    fn ignores_non_commands() {
+        let status = ServiceStatus::new();
+        assert_eq!(
+            handle_command(&status, Some("ops"), "hello", &admins()),
+            None
+        );
+        assert_eq!(
+            handle_command(&status, Some("ops"), "!unknown", &admins()),
+            None
+        );
+    }
+
+    #[test]
+    fn supported_is_public() {
+        let status = ServiceStatus::new();
+        status.set_supported(vec!["base".into(), "avalanche".into()]);
+        assert_eq!(
+            handle_command(&status, Some("guest"), "!supported", &admins()).unwrap(),
+            "Supported networks: base, avalanche"
+        );
+    }
+
+    #[test]
+    fn privileged_commands_require_admin_nick() {
+        let status = ServiceStatus::new();
+        status.set_provider_health("base", true);
+        status.set_provider_health("avalanche", false);
+        status.record_settlement("0xabc on base");
+
+        assert_eq!(
+            handle_command(&status, Some("guest"), "!health", &admins()).unwrap(),
+            "guest: not authorized"
+        );
+        assert_eq!(
+            handle_command(&status, Some("OPS"), "!health", &admins()).unwrap(),
+            "Providers 1/2 healthy: avalanche=DOWN, base=ok"
+        );
+
+        let reply = handle_command(&status, Some("ops"), "!status", &admins()).unwrap();
+        assert!(reply.starts_with("Uptime: 0s | Last settlement: 0xabc on base"));
+    }
+
+    #[test]
+    fn cooldown_limits_replies_per_nick_and_globally() {
+        let mut cooldown = Cooldown::new(NICK_COOLDOWN, GLOBAL_COOLDOWN);
+        let t0 = Instant::now();
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+
+        assert!(cooldown.allow(Some("guest"), t0));
+        // Too soon for anyone, then too soon for the same nick
+        assert!(!cooldown.allow(Some("ops"), t0));
+        assert!(!cooldown.allow(Some("GUEST"), at(2)));
+        assert!(cooldown.allow(Some("ops"), at(2)));
+        assert!(cooldown.allow(Some("guest"), at(5)));
+    }
+
+    #[test]
+    fn heartbeat_summarizes_rate_success_and_health() {
+        let status = ServiceStatus::new();
+        assert_eq!(
+            status.heartbeat_summary(0, Duration::from_secs(60)),
+            "Heartbeat: 0.0 req/min | Settle success: n/a | Providers: no data yet"
+        );
+
+        status.set_provider_health("base", true);
+        for _ in 0..3 {
+            status.record_settlement("0xabc on base");
+        }
+        status.record_settlement_failure();
+        assert_eq!(
+            status.heartbeat_summary(15, Duration::from_secs(30)),
+            "Heartbeat: 30.0 req/min | Settle success: 3/4 (75%) | Providers 1/1 healthy: base=ok"
+        );
+    }
+}
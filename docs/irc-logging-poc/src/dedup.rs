@@ -0,0 +1,162 @@
+//! Collapse repeated identical IRC lines
+//!
+//! During a failure loop the same error can be logged many times per second.
+//! Identical consecutive lines arriving within the window are suppressed and
+//! replaced by a periodic "(last message repeated N times)" summary.
+
+use std::time::{Duration, Instant};
+
+pub struct Deduplicator {
+    window: Duration,
+    last: Option<String>,
+    /// When the last identical line was seen
+    last_seen: Instant,
+    /// When the current run last reached IRC (the line itself or a summary)
+    reported_at: Instant,
+    repeats: u64,
+}
+
+impl Deduplicator {
+    /// A zero window disables de-duplication
+    pub fn new(window: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            window,
+            last: None,
+            last_seen: now,
+            reported_at: now,
+            repeats: 0,
+        }
+    }
+
+    /// Feed a queued line, returning the lines that should actually be sent
+    pub fn push(&mut self, text: &str, now: Instant) -> Vec<String> {
+        if self.window.is_zero() {
+            return vec![text.to_string()];
+        }
+
+        let mut out = Vec::new();
+        let is_repeat = self.last.as_deref() == Some(text)
+            && now.saturating_duration_since(self.last_seen) <= self.window;
+
+        if is_repeat {
+            self.repeats += 1;
+            self.last_seen = now;
+            // Keep the channel informed during a long-running loop
+            if now.saturating_duration_since(self.reported_at) >= self.window {
+                out.extend(self.take_summary());
+                self.reported_at = now;
+            }
+            return out;
+        }
+
+        out.extend(self.take_summary());
+        out.push(text.to_string());
+        self.last = Some(text.to_string());
+        self.last_seen = now;
+        self.reported_at = now;
+        out
+    }
+
+    /// When a pending repeat count should be flushed if nothing else arrives
+    pub fn deadline(&self) -> Option<Instant> {
+        (self.repeats > 0).then(|| self.last_seen + self.window)
+    }
+
+    /// Flush the pending repeat count once the run has gone quiet
+    pub fn flush(&mut self, now: Instant) -> Option<String> {
+        match self.deadline() {
+            Some(deadline) if now >= deadline => {
+                self.reported_at = now;
+                self.take_summary()
+            }
+            _ => None,
+        }
+    }
+
+    /// Report any pending repeat count immediately (used on shutdown)
+    pub fn finish(&mut self) -> Option<String> {
+        self.take_summary()
+    }
+
+    fn take_summary(&mut self) -> Option<String> {
+        if self.repeats == 0 {
+            return None;
+        }
+        let summary = format!("(last message repeated {} times)", self.repeats);
+        self.repeats = 0;
+        Some(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    #[test]
+    fn suppresses_repeats_and_reports_count_on_change() {
+        let mut dedup = Deduplicator::new(WINDOW);
+        let t0 = Instant::now();
+
+        assert_eq!(dedup.push("[ERROR] rpc down", t0), vec!["[ERROR] rpc down"]);
+        for i in 1..=3 {
+            assert!(dedup
+                .push("[ERROR] rpc down", t0 + Duration::from_secs(i))
+                .is_empty());
+        }
+        assert_eq!(
+            dedup.push("[INFO] recovered", t0 + Duration::from_secs(4)),
+            vec!["(last message repeated 3 times)", "[INFO] recovered"]
+        );
+    }
+
+    #[test]
+    fn reports_periodically_during_long_runs() {
+        let mut dedup = Deduplicator::new(WINDOW);
+        let t0 = Instant::now();
+
+        dedup.push("[ERROR] rpc down", t0);
+        let mut summaries = Vec::new();
+        for i in 1..=25 {
+            summaries.extend(dedup.push("[ERROR] rpc down", t0 + Duration::from_secs(i)));
+        }
+        assert_eq!(
+            summaries,
+            vec![
+                "(last message repeated 10 times)",
+                "(last message repeated 10 times)"
+            ]
+        );
+
+        // The trailing count is flushed once the run goes quiet
+        let quiet = t0 + Duration::from_secs(25) + WINDOW;
+        assert_eq!(dedup.deadline(), Some(quiet));
+        assert_eq!(
+            dedup.flush(quiet).as_deref(),
+            Some("(last message repeated 5 times)")
+        );
+        assert_eq!(dedup.deadline(), None);
+    }
+
+    #[test]
+    fn repeats_outside_window_are_sent_again() {
+        let mut dedup = Deduplicator::new(WINDOW);
+        let t0 = Instant::now();
+
+        dedup.push("[WARN] high load", t0);
+        assert_eq!(
+            dedup.push("[WARN] high load", t0 + WINDOW + Duration::from_secs(1)),
+            vec!["[WARN] high load"]
+        );
+    }
+
+    #[test]
+    fn zero_window_disables_dedup() {
+        let mut dedup = Deduplicator::new(Duration::ZERO);
+        let t0 = Instant::now();
+        assert_eq!(dedup.push("same", t0), vec!["same"]);
+        assert_eq!(dedup.push("same", t0), vec!["same"]);
+    }
+}
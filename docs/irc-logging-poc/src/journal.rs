@@ -0,0 +1,370 @@
+//! Append-only on-disk journal for the IRC message queue
+//!
+//! Every line enqueued by `IrcLayer` is appended as a `Q` record and every line
+//! delivered by `irc_sender_task` as a `D` record. On startup, `Q` records
+//! without a matching `D` are handed back so they can be replayed into the
+//! queue, giving at-least-once delivery across restarts.
+//!
+//! Record format (one per line, tab separated):
+//!   Q <id> <event time, unix ms> <escaped message>
+//!   D <id>
+//!
+//! Records are appended by a dedicated writer thread, so logging threads only
+//! allocate an id and hand the record over; they never wait on disk I/O.
+//! Records go through one channel in order, so a line's `D` always follows its
+//! `Q`. Dropping the `Journal` waits for the writer to finish.
+//!
+//! Compaction never rewrites the live file in place: the compacted records go
+//! to `<path>.tmp`, which is then renamed over `<path>`, so a crash or full
+//! disk mid-compaction leaves the previous journal intact.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Default journal size cap before rotation (1 MiB)
+pub const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+
+pub struct Journal {
+    next_id: AtomicU64,
+    writer: Option<mpsc::Sender<Record>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+enum Record {
+    Queued {
+        id: u64,
+        at: u64,
+        text: String,
+    },
+    Delivered(u64),
+    /// Answered once every earlier record has been written
+    Sync(mpsc::Sender<()>),
+}
+
+/// File state, owned by the writer thread
+struct Writer {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    size: u64,
+    /// Undelivered lines with their event time (unix ms), oldest first
+    pending: BTreeMap<u64, (u64, String)>,
+}
+
+/// A line recorded but not yet delivered
+pub struct PendingLine {
+    pub id: u64,
+    pub at: SystemTime,
+    pub text: String,
+}
+
+impl Journal {
+    /// Open (or create) the journal at `path`, returning it together with the
+    /// lines that were enqueued but never delivered by a previous run
+    pub fn open(path: impl AsRef<Path>, max_bytes: u64) -> io::Result<(Self, Vec<PendingLine>)> {
+        let path = path.as_ref().to_path_buf();
+        let mut pending = BTreeMap::new();
+        let mut next_id = 0;
+
+        if path.exists() {
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let mut parts = line.splitn(4, '\t');
+                let kind = parts.next();
+                let Some(id) = parts.next().and_then(|id| id.parse::<u64>().ok()) else {
+                    continue; // Torn write from a crash, skip it
+                };
+                let at = parts.next().and_then(|at| at.parse::<u64>().ok());
+                match (kind, at, parts.next()) {
+                    (Some("Q"), Some(at), Some(text)) => {
+                        pending.insert(id, (at, unescape(text)));
+                    }
+                    (Some("D"), _, _) => {
+                        pending.remove(&id);
+                    }
+                    _ => continue,
+                }
+                next_id = next_id.max(id + 1);
+            }
+        }
+
+        let replay = pending
+            .iter()
+            .map(|(id, (at, text))| PendingLine {
+                id: *id,
+                at: UNIX_EPOCH + Duration::from_millis(*at),
+                text: text.clone(),
+            })
+            .collect();
+
+        // Start from a compacted file so the journal doesn't grow across restarts
+        let file = write_compacted(&path, &pending)?;
+        let size = file.metadata()?.len();
+
+        let mut writer = Writer {
+            path,
+            max_bytes,
+            file,
+            size,
+            pending,
+        };
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::Builder::new()
+            .name("irc-journal".to_string())
+            .spawn(move || {
+                for record in rx {
+                    if let Err(e) = writer.apply(record) {
+                        eprintln!("Failed to write IRC journal: {}", e);
+                    }
+                }
+            })?;
+
+        let journal = Journal {
+            next_id: AtomicU64::new(next_id),
+            writer: Some(tx),
+            handle: Some(handle),
+        };
+        Ok((journal, replay))
+    }
+
+    /// Record an enqueued line and its event time, returning its journal id.
+    /// The write itself happens on the writer thread.
+    pub fn record(&self, text: &str, at: SystemTime) -> u64 {
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.send(Record::Queued {
+            id,
+            at,
+            text: text.to_string(),
+        });
+        id
+    }
+
+    /// Mark a previously recorded line as delivered
+    pub fn mark_delivered(&self, id: u64) {
+        self.send(Record::Delivered(id));
+    }
+
+    /// Wait until everything recorded so far has been written. Call before
+    /// exiting while the journal is still shared (e.g. held by `IrcLayer`).
+    pub fn sync(&self) {
+        let (done, wait) = mpsc::channel();
+        self.send(Record::Sync(done));
+        let _ = wait.recv();
+    }
+
+    fn send(&self, record: Record) {
+        if let Some(writer) = &self.writer {
+            // Only fails if the writer thread died; it already reported why
+            let _ = writer.send(record);
+        }
+    }
+}
+
+impl Drop for Journal {
+    /// Close the channel and wait for the writer to append what's left
+    fn drop(&mut self) {
+        drop(self.writer.take());
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Writer {
+    fn apply(&mut self, record: Record) -> io::Result<()> {
+        match record {
+            Record::Queued { id, at, text } => {
+                let line = queued_record(id, at, &text);
+                self.pending.insert(id, (at, text));
+                self.append(&line)
+            }
+            Record::Delivered(id) => {
+                if self.pending.remove(&id).is_none() {
+                    return Ok(());
+                }
+                self.append(&format!("D\t{}\n", id))
+            }
+            Record::Sync(done) => {
+                let _ = done.send(());
+                Ok(())
+            }
+        }
+    }
+
+    fn append(&mut self, record: &str) -> io::Result<()> {
+        self.file.write_all(record.as_bytes())?;
+        self.size += record.len() as u64;
+        if self.size > self.max_bytes {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Copy the current journal to `<path>.1` and replace it with one holding
+    /// only undelivered lines. If those alone exceed half the cap, the oldest
+    /// are dropped so rotation always makes room.
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut pending_bytes: u64 = self
+            .pending
+            .iter()
+            .map(|(id, (at, text))| queued_record(*id, *at, text).len() as u64)
+            .sum();
+        let mut dropped = 0;
+        while pending_bytes > self.max_bytes / 2 {
+            let Some((id, (at, text))) = self.pending.pop_first() else {
+                break;
+            };
+            pending_bytes -= queued_record(id, at, &text).len() as u64;
+            dropped += 1;
+        }
+        if dropped > 0 {
+            eprintln!(
+                "IRC journal full, dropped {} undelivered lines (oldest first)",
+                dropped
+            );
+        }
+
+        fs::copy(&self.path, sibling(&self.path, ".1"))?;
+
+        self.file = write_compacted(&self.path, &self.pending)?;
+        self.size = pending_bytes;
+        Ok(())
+    }
+}
+
+/// Atomically replace the journal at `path` with just the `pending` records,
+/// returning it opened for appending
+fn write_compacted(path: &Path, pending: &BTreeMap<u64, (u64, String)>) -> io::Result<File> {
+    let tmp = sibling(path, ".tmp");
+    let mut file = File::create(&tmp)?;
+    for (id, (at, text)) in pending {
+        file.write_all(queued_record(*id, *at, text).as_bytes())?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+/// `path` with `suffix` appended, e.g. `irc.journal` -> `irc.journal.tmp`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn queued_record(id: u64, at: u64, text: &str) -> String {
+    format!("Q\t{}\t{}\t{}\n", id, at, escape(text))
+}
+
+/// Escape backslashes, tabs and newlines so each record stays on one line
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("irc-journal-{}-{}.log", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn replays_undelivered_lines_after_restart() {
+        let path = temp_path("replay");
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        {
+            let (journal, replay) = Journal::open(&path, DEFAULT_MAX_BYTES).unwrap();
+            assert!(replay.is_empty());
+            let delivered = journal.record("[INFO] delivered", at);
+            journal.record("[ERROR] line one\nline two", at);
+            journal.mark_delivered(delivered);
+        }
+
+        let (journal, replay) = Journal::open(&path, DEFAULT_MAX_BYTES).unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].text, "[ERROR] line one\nline two");
+        assert_eq!(replay[0].at, at);
+
+        // New ids must not collide with replayed ones
+        assert!(journal.record("[INFO] next", at) > replay[0].id);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn leftover_temp_file_does_not_affect_replay() {
+        let path = temp_path("tmp");
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        {
+            let (journal, _) = Journal::open(&path, DEFAULT_MAX_BYTES).unwrap();
+            journal.record("[WARN] pending", at);
+        }
+        // A compaction that crashed halfway through writing its temp file
+        fs::write(sibling(&path, ".tmp"), "Q\t9\t0\t[INFO] tor").unwrap();
+
+        let (_, replay) = Journal::open(&path, DEFAULT_MAX_BYTES).unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].text, "[WARN] pending");
+        assert!(!sibling(&path, ".tmp").exists());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rotation_keeps_pending_lines_within_cap() {
+        let path = temp_path("rotate");
+        let (journal, _) = Journal::open(&path, 256).unwrap();
+        for i in 0..50 {
+            let id = journal.record(&format!("[INFO] message {}", i), SystemTime::now());
+            if i % 2 == 0 {
+                journal.mark_delivered(id);
+            }
+        }
+        drop(journal);
+        assert!(fs::metadata(&path).unwrap().len() <= 256);
+
+        let (_, replay) = Journal::open(&path, 256).unwrap();
+        assert!(!replay.is_empty());
+        assert!(replay.iter().all(|line| {
+            let n: u32 = line.text.rsplit(' ').next().unwrap().parse().unwrap();
+            n % 2 == 1
+        }));
+        // The newest undelivered line always survives rotation
+        assert_eq!(replay.last().unwrap().text, "[INFO] message 49");
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(sibling(&path, ".1"));
+    }
+}
@@ -1,21 +1,40 @@
-/// IRC Logging Proof of Concept
-///
-/// This demonstrates how to integrate IRC logging with tracing-subscriber
-/// for real-time log streaming to an IRC channel.
-///
-/// Usage:
-///   IRC_ENABLED=true IRC_SERVER=irc.libera.chat IRC_CHANNEL=#test cargo run
-///
-/// Then join the IRC channel with your favorite client to see logs appear in real-time.
+//! IRC Logging Proof of Concept
+//!
+//! This demonstrates how to integrate IRC logging with tracing-subscriber
+//! for real-time log streaming to an IRC channel.
+//!
+//! Usage:
+//!   IRC_ENABLED=true IRC_SERVER=irc.libera.chat IRC_CHANNEL=#test cargo run
+//!
+//! Then join the IRC channel with your favorite client to see logs appear in real-time.
+
+mod ack;
+mod commands;
+mod dedup;
+mod journal;
+mod shutdown;
+
+use futures::StreamExt;
 
 use irc::client::prelude::*;
 use once_cell::sync::Lazy;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::trace::SdkTracerProvider;
 use regex::Regex;
 use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, info_span, warn, Instrument};
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
+use crate::ack::AckTracker;
+use crate::dedup::Deduplicator;
+use crate::journal::Journal;
+use crate::shutdown::Shutdown;
+
 /// Sanitization patterns for sensitive data
 static PRIVATE_KEY_PATTERN: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"0x[a-fA-F0-9]{64}").unwrap());
@@ -46,30 +65,169 @@ fn sanitize_message(msg: &str) -> String {
     sanitized
 }
 
-/// Truncate messages to IRC's 510-byte limit
-fn truncate_irc_message(msg: &str) -> String {
-    const MAX_LEN: usize = 400; // Leave room for channel name + protocol overhead
-    if msg.len() <= MAX_LEN {
-        msg.to_string()
-    } else {
-        format!("{}... [truncated]", &msg[..MAX_LEN])
+/// Maximum line length, well under IRC's 510-byte limit to leave room for
+/// channel name + protocol overhead
+const MAX_IRC_LEN: usize = 400;
+
+/// Truncate messages to at most `max_len` bytes (on a char boundary)
+fn truncate_irc_message(msg: &str, max_len: usize) -> String {
+    if msg.len() <= max_len {
+        return msg.to_string();
+    }
+    let mut end = max_len;
+    while !msg.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... [truncated]", &msg[..end])
+}
+
+/// Width of the `HH:MM:SS ` timestamp prepended on delivery
+const TIMESTAMP_WIDTH: usize = 9;
+
+/// Where delivered lines show their event time (`HH:MM:SS`, UTC)
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Timestamps {
+    /// Not at all (`IRC_TIMESTAMPS=false`)
+    Off,
+    /// Prepended to the line (the default)
+    Prepend,
+    /// In place of the `{ts}` placeholder in `IRC_PREFIX`
+    Prefix,
+}
+
+impl Timestamps {
+    /// Bytes the stamp adds to a queued line
+    fn width(self) -> usize {
+        match self {
+            Timestamps::Off => 0,
+            Timestamps::Prepend => TIMESTAMP_WIDTH,
+            Timestamps::Prefix => "HH:MM:SS".len() - "{ts}".len(),
+        }
+    }
+}
+
+/// Join a rendered prefix and message into one IRC line of at most `max_len`
+/// bytes. Only the message is truncated, so the prefix always survives.
+fn format_irc_line(prefix: &str, message: &str, max_len: usize) -> String {
+    if prefix.is_empty() {
+        return truncate_irc_message(message, max_len);
+    }
+    let budget = max_len.saturating_sub(prefix.len() + 1);
+    format!("{} {}", prefix, truncate_irc_message(message, budget))
+}
+
+/// Add the event's `HH:MM:SS` time to a line being delivered
+fn stamp_line(text: &str, at: SystemTime, timestamps: Timestamps) -> String {
+    match timestamps {
+        Timestamps::Off => text.to_string(),
+        Timestamps::Prepend => format!("{} {}", format_hms(at), text),
+        // The prefix comes first, so its `{ts}` is the first one in the line
+        Timestamps::Prefix => text.replacen("{ts}", &format_hms(at), 1),
+    }
+}
+
+/// Add the time to a `(last message repeated N times)` summary. Summaries
+/// have no prefix, so the time is prepended whenever timestamps are on.
+fn stamp_summary(summary: &str, at: SystemTime, timestamps: Timestamps) -> String {
+    match timestamps {
+        Timestamps::Off => summary.to_string(),
+        Timestamps::Prepend | Timestamps::Prefix => format!("{} {}", format_hms(at), summary),
+    }
+}
+
+/// UTC wall-clock time of day as `HH:MM:SS`
+fn format_hms(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+/// Template for the prefix of forwarded lines (`IRC_PREFIX`).
+///
+/// Placeholders: `{level}`, `{target}`, `{host}`, `{ts}` (UTC `HH:MM:SS`) and
+/// `{network}` (from the event or an enclosing span's `network` field, empty
+/// if none). The default `[{level}]` gives lines like `[INFO] message`.
+///
+/// `{ts}` (at most once) is left in the queued line and filled in on delivery
+/// by `stamp_line`, so repeated lines still de-duplicate and the time isn't
+/// also prepended.
+struct IrcPrefix {
+    template: String,
+    host: String,
+}
+
+impl IrcPrefix {
+    const DEFAULT: &'static str = "[{level}]";
+
+    fn from_env() -> Self {
+        let template = env::var("IRC_PREFIX").unwrap_or_else(|_| Self::DEFAULT.to_string());
+        let host = env::var("HOSTNAME")
+            .ok()
+            .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+            .map(|h| h.trim().to_string())
+            .filter(|h| !h.is_empty())
+            .unwrap_or_else(|| "unknown".to_string());
+        Self { template, host }
+    }
+
+    /// Whether delivered lines get their time through `{ts}`
+    fn uses_ts(&self) -> bool {
+        self.template.contains("{ts}")
+    }
+
+    fn render(&self, level: &tracing::Level, target: &str, network: Option<&str>) -> String {
+        self.template
+            .replace("{level}", level.as_str())
+            .replace("{target}", target)
+            .replace("{host}", &self.host)
+            .replace("{network}", network.unwrap_or(""))
+            .trim()
+            .to_string()
     }
 }
 
+/// A sanitized line waiting in the IRC queue
+struct QueuedLine {
+    /// Journal id, if the queue is persisted to disk
+    journal_id: Option<u64>,
+    /// Wall-clock time of the event
+    at: SystemTime,
+    text: String,
+}
+
 /// Custom tracing layer that forwards logs to IRC
 struct IrcLayer {
-    tx: mpsc::UnboundedSender<String>,
+    tx: mpsc::UnboundedSender<QueuedLine>,
+    journal: Option<Arc<Journal>>,
+    prefix: IrcPrefix,
+    /// How delivered lines show the event time
+    timestamps: Timestamps,
+    /// Serializes stamping and enqueueing so queue order matches event time
+    enqueue: Mutex<()>,
+    /// Only forward logs for these networks (`IRC_NETWORK_FILTER`, lowercase).
+    /// Logs without a network are always forwarded.
+    network_filter: Option<Vec<String>>,
 }
 
-/// Visitor to extract the formatted message from a tracing event
+/// `network` field of a span, kept in its extensions for events inside it
+struct SpanNetwork(String);
+
+/// Visitor to extract the formatted message (and `network` tag) from a
+/// tracing event or span
 struct MessageVisitor {
     message: String,
+    network: Option<String>,
 }
 
 impl MessageVisitor {
     fn new() -> Self {
         Self {
             message: String::new(),
+            network: None,
         }
     }
 }
@@ -82,18 +240,67 @@ impl tracing::field::Visit for MessageVisitor {
             if self.message.starts_with('"') && self.message.ends_with('"') {
                 self.message = self.message[1..self.message.len() - 1].to_string();
             }
+        } else if field.name() == "network" {
+            self.network = Some(format!("{:?}", value).trim_matches('"').to_string());
+        }
+    }
+}
+
+impl IrcLayer {
+    /// Whether a log tagged with `network` passes `IRC_NETWORK_FILTER`
+    fn network_allowed(&self, network: Option<&str>) -> bool {
+        match (&self.network_filter, network) {
+            (Some(filter), Some(network)) => filter.iter().any(|n| n.eq_ignore_ascii_case(network)),
+            _ => true,
         }
     }
 }
 
+/// Parse `IRC_NETWORK_FILTER` (comma-separated). An empty list means no
+/// filter rather than dropping every network-tagged line.
+fn parse_network_filter(value: &str) -> Option<Vec<String>> {
+    let networks: Vec<String> = value
+        .split(',')
+        .map(|network| network.trim().to_lowercase())
+        .filter(|network| !network.is_empty())
+        .collect();
+    (!networks.is_empty()).then_some(networks)
+}
+
 impl<S> Layer<S> for IrcLayer
 where
-    S: tracing::Subscriber,
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
 {
+    fn on_new_span(
+        &self,
+        attrs: &tracing::span::Attributes<'_>,
+        id: &tracing::span::Id,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut visitor = MessageVisitor::new();
+        attrs.record(&mut visitor);
+        if let (Some(network), Some(span)) = (visitor.network, ctx.span(id)) {
+            span.extensions_mut().replace(SpanNetwork(network));
+        }
+    }
+
+    fn on_record(
+        &self,
+        id: &tracing::span::Id,
+        values: &tracing::span::Record<'_>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
+    ) {
+        let mut visitor = MessageVisitor::new();
+        values.record(&mut visitor);
+        if let (Some(network), Some(span)) = (visitor.network, ctx.span(id)) {
+            span.extensions_mut().replace(SpanNetwork(network));
+        }
+    }
+
     fn on_event(
         &self,
         event: &tracing::Event<'_>,
-        _ctx: tracing_subscriber::layer::Context<'_, S>,
+        ctx: tracing_subscriber::layer::Context<'_, S>,
     ) {
         let metadata = event.metadata();
 
@@ -105,10 +312,29 @@ where
             return;
         }
 
+        // The OTLP exporter reports its own failures (e.g. an unreachable
+        // collector) through tracing; keep those on the console only so an
+        // OpenTelemetry outage can't flood the channel
+        if metadata.target().starts_with("opentelemetry") {
+            return;
+        }
+
         // Extract the actual log message using visitor
         let mut visitor = MessageVisitor::new();
         event.record(&mut visitor);
 
+        // Network from the event itself, else the nearest enclosing span
+        let network = visitor.network.take().or_else(|| {
+            ctx.event_scope(event)?.find_map(|span| {
+                span.extensions()
+                    .get::<SpanNetwork>()
+                    .map(|network| network.0.clone())
+            })
+        });
+        if !self.network_allowed(network.as_deref()) {
+            return;
+        }
+
         // If we got a message, use it; otherwise use target
         let content = if !visitor.message.is_empty() {
             visitor.message
@@ -116,32 +342,181 @@ where
             format!("Event in {}", metadata.target())
         };
 
-        // Format the message with level and content
-        let msg = format!("[{}] {}", metadata.level(), content);
+        // Format the line with the configured prefix, then sanitize and
+        // truncate the message part, leaving room for the delivery timestamp
+        let prefix = self
+            .prefix
+            .render(metadata.level(), metadata.target(), network.as_deref());
+        let max_len = MAX_IRC_LEN - self.timestamps.width();
+        let truncated = format_irc_line(&prefix, &sanitize_message(&content), max_len);
 
-        // Sanitize and truncate
-        let sanitized = sanitize_message(&msg);
-        let truncated = truncate_irc_message(&sanitized);
+        // Stamp and enqueue under one lock so concurrent events enter the
+        // (FIFO) queue in timestamp order. Nothing here touches the disk; the
+        // journal hands the record to its writer thread.
+        let _guard = self.enqueue.lock().unwrap_or_else(|e| e.into_inner());
+        let at = SystemTime::now();
+
+        // Record in the journal first so the line survives a restart
+        let journal_id = self
+            .journal
+            .as_ref()
+            .map(|journal| journal.record(&truncated, at));
 
         // Send to IRC channel (non-blocking)
-        let _ = self.tx.send(truncated);
+        let _ = self.tx.send(QueuedLine {
+            journal_id,
+            at,
+            text: truncated,
+        });
     }
 }
 
-/// Background task that sends queued messages to IRC
-async fn irc_sender_task(
-    mut rx: mpsc::UnboundedReceiver<String>,
+/// Send one line to the channel, honoring the rate limit
+async fn send_rate_limited(client: &Client, channel: &str, text: &str) -> irc::error::Result<()> {
+    // Rate limiting: 1 message per 500ms = 2 msg/sec (safe)
+    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+
+    client.send_privmsg(channel, text)?;
+
+    // Successfully sent, echo to stderr for debugging (stdout is reserved for
+    // the log output, which may be JSON lines)
+    eprintln!("[IRC->{}] {}", channel, text);
+    Ok(())
+}
+
+/// Deliver a queued line (subject to de-duplication). It stays unacknowledged
+/// in `acks` until the server answers the next PING barrier.
+async fn deliver_line(
+    client: &Client,
+    channel: &str,
+    dedup: &mut Deduplicator,
+    acks: &mut AckTracker,
+    line: QueuedLine,
+    timestamps: Timestamps,
+) -> irc::error::Result<()> {
+    // De-duplicate on the message itself; timestamps are added on the way out.
+    // Anything other than the line itself is a repeat-count summary.
+    let texts: Vec<String> = dedup
+        .push(&line.text, Instant::now())
+        .iter()
+        .map(|text| {
+            if *text == line.text {
+                stamp_line(text, line.at, timestamps)
+            } else {
+                stamp_summary(text, line.at, timestamps)
+            }
+        })
+        .collect();
+
+    let mut result = Ok(());
+    for text in &texts {
+        if let Err(e) = send_rate_limited(client, channel, text).await {
+            error!("Failed to send IRC message '{}': {}", text, e);
+            result = Err(e);
+            break;
+        }
+    }
+
+    // Tracked even on failure, so the line is resent after reconnecting
+    acks.sent(line.journal_id, texts);
+    result
+}
+
+/// Resend lines that were never acknowledged on the previous connection
+async fn resend_unacked(
+    client: &Client,
+    channel: &str,
+    acks: &mut AckTracker,
+) -> irc::error::Result<()> {
+    for line in acks.take_unacked() {
+        let mut result = Ok(());
+        for text in &line.texts {
+            result = send_rate_limited(client, channel, text).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        acks.sent(line.journal_id, line.texts);
+        result?;
+    }
+    send_barrier(client, acks)
+}
+
+/// Queue a PING whose PONG acknowledges every line sent so far
+fn send_barrier(client: &Client, acks: &mut AckTracker) -> irc::error::Result<()> {
+    match acks.barrier(Instant::now()) {
+        Some(token) => client.send(Command::PING(token, None)),
+        None => Ok(()),
+    }
+}
+
+/// Mark acknowledged lines delivered in the journal
+fn confirm_delivered(journal: Option<&Journal>, ids: Vec<u64>) {
+    let Some(journal) = journal else {
+        return;
+    };
+    for id in ids {
+        journal.mark_delivered(id);
+    }
+}
+
+/// Wait before reconnecting. Returns false if shutdown started meanwhile.
+async fn reconnect_backoff(shutdown: &CancellationToken) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => true,
+        _ = shutdown.cancelled() => false,
+    }
+}
+
+/// Settings for `irc_sender_task`
+struct IrcSenderOptions {
     channel: String,
     config: Config,
+    /// Nicks allowed to run privileged bot commands
+    admins: Vec<String>,
+    /// Window for collapsing repeated lines (zero disables)
+    dedup_window: Duration,
+    /// How delivered lines show the event time
+    timestamps: Timestamps,
+}
+
+/// Background task that sends queued messages to IRC and answers bot commands.
+///
+/// Lines are delivered in queue (FIFO) order and marked delivered in the
+/// journal once the server acknowledges them (see `ack`). When `shutdown` is
+/// cancelled (after the application tasks have finished) or the queue closes,
+/// it drains whatever is already queued, reports any pending repeat count,
+/// sends QUIT and keeps the connection polled until the server closes it (the
+/// grace period in `Shutdown` bounds the wait). If it isn't connected at that
+/// point it exits immediately; unacknowledged journaled lines are replayed on
+/// the next start.
+async fn irc_sender_task(
+    mut rx: mpsc::UnboundedReceiver<QueuedLine>,
+    options: IrcSenderOptions,
+    journal: Option<Arc<Journal>>,
+    shutdown: CancellationToken,
 ) {
-    loop {
+    let IrcSenderOptions {
+        channel,
+        config,
+        admins,
+        dedup_window,
+        timestamps,
+    } = options;
+    let mut dedup = Deduplicator::new(dedup_window);
+    let mut acks = AckTracker::new(Instant::now());
+    let mut cooldown = commands::Cooldown::new(commands::NICK_COOLDOWN, commands::GLOBAL_COOLDOWN);
+
+    while !shutdown.is_cancelled() {
         match Client::from_config(config.clone()).await {
-            Ok(client) => {
+            Ok(mut client) => {
                 info!("Connected to IRC server, identifying...");
 
                 if let Err(e) = client.identify() {
                     error!("Failed to identify with IRC server: {}", e);
-                    tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+                    if !reconnect_backoff(&shutdown).await {
+                        return;
+                    }
                     continue;
                 }
 
@@ -151,36 +526,237 @@ async fn irc_sender_task(
                 if let Err(e) = client.send_privmsg(&channel, "IRC logging initialized") {
                     error!("Failed to send initial message to IRC: {}", e);
                     error!("Channel might not exist or bot might be banned");
-                    tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+                    if !reconnect_backoff(&shutdown).await {
+                        return;
+                    }
+                    continue;
+                }
+
+                // Inbound messages (commands, and PINGs handled by the client)
+                let mut stream = match client.stream() {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("Failed to open IRC message stream: {}", e);
+                        if !reconnect_backoff(&shutdown).await {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                // Lines the previous connection never acknowledged go out first
+                if let Err(e) = resend_unacked(&client, &channel, &mut acks).await {
+                    error!("Failed to resend unacknowledged IRC lines: {}", e);
+                    if !reconnect_backoff(&shutdown).await {
+                        return;
+                    }
                     continue;
                 }
 
-                // Message sending loop with rate limiting
-                while let Some(msg) = rx.recv().await {
-                    // Rate limiting: 1 message per 500ms = 2 msg/sec (safe)
-                    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-
-                    if let Err(e) = client.send_privmsg(&channel, &msg) {
-                        error!("Failed to send IRC message '{}': {}", msg, e);
-                        // Connection lost, break and reconnect
-                        break;
-                    } else {
-                        // Successfully sent, log to console for debugging
-                        println!("[IRC->{}] {}", channel, msg);
+                // Message sending loop with rate limiting. Ends with true when
+                // the sender should flush and leave, false to reconnect.
+                let closing = loop {
+                    // Wakes the loop to report a repeat count once a run goes quiet
+                    let flush_at = dedup.deadline().map(tokio::time::Instant::from_std);
+
+                    tokio::select! {
+                        inbound = stream.next() => {
+                            let message = match inbound {
+                                Some(Ok(message)) => message,
+                                Some(Err(e)) => {
+                                    error!("IRC connection error: {}", e);
+                                    break false;
+                                }
+                                // Connection closed, reconnect
+                                None => break false,
+                            };
+
+                            let (target, text) = match &message.command {
+                                Command::PRIVMSG(target, text) => (target, text),
+                                Command::PONG(server, token) => {
+                                    // Servers echo the token in either position
+                                    let token = token.as_deref().unwrap_or(server);
+                                    confirm_delivered(journal.as_deref(), acks.acknowledge(token));
+                                    continue;
+                                }
+                                _ => continue,
+                            };
+                            // Channel names are case-insensitive; the server may
+                            // relay a different casing than IRC_CHANNEL
+                            if !target.eq_ignore_ascii_case(&channel) {
+                                continue;
+                            }
+
+                            let nick = message.source_nickname();
+                            let Some(reply) =
+                                commands::handle_command(&commands::STATUS, nick, text, &admins)
+                            else {
+                                continue;
+                            };
+                            // Drop commands that arrive too fast rather than flood
+                            if !cooldown.allow(nick, Instant::now()) {
+                                continue;
+                            }
+                            let reply =
+                                truncate_irc_message(&sanitize_message(&reply), MAX_IRC_LEN);
+                            if let Err(e) = send_rate_limited(&client, &channel, &reply).await {
+                                error!("Failed to answer IRC command '{}': {}", text, e);
+                                break false;
+                            }
+                        }
+                        line = rx.recv() => {
+                            // Queue closed, the application is shutting down
+                            let Some(line) = line else {
+                                break true;
+                            };
+
+                            let delivered = deliver_line(
+                                &client,
+                                &channel,
+                                &mut dedup,
+                                &mut acks,
+                                line,
+                                timestamps,
+                            )
+                            .await;
+                            // Ask the server to confirm the batch once the queue is
+                            // idle, or periodically if it never is
+                            let delivered = delivered.and_then(|()| {
+                                if rx.is_empty() || acks.barrier_due(Instant::now()) {
+                                    send_barrier(&client, &mut acks)
+                                } else {
+                                    Ok(())
+                                }
+                            });
+                            if delivered.is_err() {
+                                // Connection lost, break and reconnect
+                                break false;
+                            }
+                        }
+                        _ = tokio::time::sleep_until(
+                            flush_at.unwrap_or_else(tokio::time::Instant::now)
+                        ), if flush_at.is_some() => {
+                            if let Some(summary) = dedup.flush(Instant::now()) {
+                                let summary =
+                                    stamp_summary(&summary, SystemTime::now(), timestamps);
+                                let sent = send_rate_limited(&client, &channel, &summary).await;
+                                if let Err(e) = sent {
+                                    error!("Failed to send IRC message '{}': {}", summary, e);
+                                    break false;
+                                }
+                            }
+                        }
+                        _ = shutdown.cancelled() => break true,
+                    }
+                };
+                if !closing {
+                    continue;
+                }
+
+                // Flush what's already queued, then leave cleanly
+                while let Ok(line) = rx.try_recv() {
+                    let delivered = deliver_line(
+                        &client,
+                        &channel,
+                        &mut dedup,
+                        &mut acks,
+                        line,
+                        timestamps,
+                    )
+                    .await;
+                    if delivered.is_err() {
+                        return;
+                    }
+                }
+                if let Some(summary) = dedup.finish() {
+                    let summary = stamp_summary(&summary, SystemTime::now(), timestamps);
+                    let _ = send_rate_limited(&client, &channel, &summary).await;
+                }
+                if client.send_quit("Shutting down").is_err() {
+                    return;
+                }
+
+                // The client only writes to the socket while its stream
+                // is polled, so keep polling until the server hangs up
+                while let Some(message) = stream.next().await {
+                    match message {
+                        Ok(Message {
+                            command: Command::PONG(server, token),
+                            ..
+                        }) => {
+                            let token = token.as_deref().unwrap_or(&server);
+                            let acked = acks.acknowledge(token);
+                            confirm_delivered(journal.as_deref(), acked);
+                        }
+                        Ok(_) => {}
+                        Err(_) => return,
                     }
                 }
+
+                // The server closed the connection after our QUIT, so
+                // it received everything sent before it
+                confirm_delivered(journal.as_deref(), acks.acknowledge_all());
+                return;
             }
             Err(e) => {
                 error!("IRC connection failed: {}, retrying in 30s...", e);
-                tokio::time::sleep(tokio::time::Duration::from_secs(30)).await;
+                if !reconnect_backoff(&shutdown).await {
+                    return;
+                }
             }
         }
     }
 }
 
-/// Initialize tracing with optional IRC layer
-fn init_tracing() {
-    let irc_layer = if env::var("IRC_ENABLED").is_ok() {
+/// Build an OTLP trace exporter when `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+/// The exporter reads the endpoint (and the other standard `OTEL_*`
+/// variables) from the environment itself.
+fn init_otel() -> Option<SdkTracerProvider> {
+    let endpoint = env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+    let exporter = match opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()
+    {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            eprintln!("Failed to create OTLP exporter for {}: {}", endpoint, e);
+            return None;
+        }
+    };
+
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(env!("CARGO_PKG_NAME"))
+        .build();
+
+    eprintln!("OpenTelemetry export enabled: {}", endpoint);
+    Some(
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build(),
+    )
+}
+
+/// JSON console layer (`LOG_FORMAT=json`): one object per line with the event
+/// fields at the top level and the enclosing spans' fields alongside
+fn json_console_layer<S, W>(writer: W) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_writer(writer)
+}
+
+/// Initialize tracing with optional IRC and OpenTelemetry layers. The IRC
+/// sender is registered with `shutdown` so its queue is flushed on exit.
+/// Returns the tracer provider and IRC journal, if any, so they can be
+/// flushed too.
+fn init_tracing(shutdown: &mut Shutdown) -> (Option<SdkTracerProvider>, Option<Arc<Journal>>) {
+    let (irc_layer, journal) = if env::var("IRC_ENABLED").is_ok() {
         let (tx, rx) = mpsc::unbounded_channel();
 
         let server = env::var("IRC_SERVER").unwrap_or_else(|_| "irc.dal.net".to_string());
@@ -197,62 +773,682 @@ fn init_tracing() {
             ..Default::default()
         };
 
+        // Optional on-disk journal so queued lines survive a restart
+        let journal = match env::var("IRC_JOURNAL_PATH") {
+            Ok(path) => {
+                let max_bytes = env::var("IRC_JOURNAL_MAX_BYTES")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(journal::DEFAULT_MAX_BYTES);
+
+                match Journal::open(&path, max_bytes) {
+                    Ok((journal, replay)) => {
+                        eprintln!(
+                            "IRC journal: {} ({} undelivered lines replayed)",
+                            path,
+                            replay.len()
+                        );
+                        // Undelivered lines from the previous run go out first
+                        for line in replay {
+                            let _ = tx.send(QueuedLine {
+                                journal_id: Some(line.id),
+                                at: line.at,
+                                text: line.text,
+                            });
+                        }
+                        Some(Arc::new(journal))
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to open IRC journal {}: {}", path, e);
+                        None
+                    }
+                }
+            }
+            Err(_) => None,
+        };
+
+        // Nicks allowed to run privileged bot commands (!status, !health)
+        let admins: Vec<String> = env::var("IRC_ADMIN_NICKS")
+            .map(|v| {
+                v.split(',')
+                    .map(|nick| nick.trim().to_string())
+                    .filter(|nick| !nick.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        // Identical consecutive lines within this window are collapsed (0 disables)
+        let dedup_window = env::var("IRC_DEDUP_WINDOW_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(10));
+
+        // Delivered lines show the event time (UTC): in place of `{ts}` if the
+        // prefix has one, otherwise prepended as HH:MM:SS unless disabled
+        let prefix = IrcPrefix::from_env();
+        let timestamps = if prefix.uses_ts() {
+            Timestamps::Prefix
+        } else if env::var("IRC_TIMESTAMPS").map_or(true, |v| v != "false") {
+            Timestamps::Prepend
+        } else {
+            Timestamps::Off
+        };
+
         // Spawn background IRC sender
-        tokio::spawn(irc_sender_task(rx, channel.clone(), config));
+        let options = IrcSenderOptions {
+            channel: channel.clone(),
+            config,
+            admins,
+            dedup_window,
+            timestamps,
+        };
+        let sender = tokio::spawn(irc_sender_task(
+            rx,
+            options,
+            journal.clone(),
+            shutdown.sink_token(),
+        ));
+        shutdown.register_sink("irc sender", sender);
 
-        println!(
+        eprintln!(
             "IRC logging enabled: {}:{} as {}",
             server, channel, nickname
         );
 
-        Some(IrcLayer { tx })
+        // Only forward logs for these networks (untagged logs always pass)
+        let network_filter = env::var("IRC_NETWORK_FILTER")
+            .ok()
+            .and_then(|v| parse_network_filter(&v));
+
+        let layer = IrcLayer {
+            tx,
+            journal: journal.clone(),
+            prefix,
+            timestamps,
+            enqueue: Mutex::new(()),
+            network_filter,
+        };
+        (Some(layer), journal)
     } else {
-        println!("IRC logging disabled (set IRC_ENABLED=true to enable)");
-        None
+        eprintln!("IRC logging disabled (set IRC_ENABLED=true to enable)");
+        (None, None)
     };
 
-    // Build subscriber with console + optional IRC layer
-    let subscriber = tracing_subscriber::registry()
+    // Console output: human-readable by default, JSON lines for log aggregation.
+    // The IRC layer always formats its own human-readable line.
+    let json_console = env::var("LOG_FORMAT")
+        .map(|v| v.eq_ignore_ascii_case("json"))
+        .unwrap_or(false);
+    let (pretty_layer, json_layer) = if json_console {
+        (None, Some(json_console_layer(std::io::stdout)))
+    } else {
+        (Some(tracing_subscriber::fmt::layer()), None)
+    };
+
+    let otel_provider = init_otel();
+    let otel_layer = otel_provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    // Build subscriber with console + optional IRC and OpenTelemetry layers
+    tracing_subscriber::registry()
         .with(tracing_subscriber::filter::LevelFilter::INFO)
-        .with(tracing_subscriber::fmt::layer());
+        .with(pretty_layer)
+        .with(json_layer)
+        .with(irc_layer)
+        .with(otel_layer)
+        .init();
 
-    if let Some(irc_layer) = irc_layer {
-        subscriber.with(irc_layer).init();
-    } else {
-        subscriber.init();
+    (otel_provider, journal)
+}
+
+/// Periodically log a one-line metrics summary. It goes through the normal
+/// tracing path, so it reaches the console and (when enabled) IRC.
+async fn heartbeat_task(interval: Duration, shutdown: CancellationToken) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await; // First tick fires immediately
+    let mut last_requests = commands::STATUS.requests();
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.cancelled() => return,
+        }
+        let requests = commands::STATUS.requests();
+        info!(
+            "{}",
+            commands::STATUS.heartbeat_summary(requests - last_requests, interval)
+        );
+        last_requests = requests;
+    }
+}
+
+/// Simulate some application activity
+async fn simulate_activity() {
+    for i in 1..=10 {
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+
+        // Requests alternate between networks; logs inside carry the tag
+        let network = if i % 2 == 0 { "avalanche" } else { "base" };
+        simulate_request(i, network)
+            .instrument(info_span!("request", network))
+            .await;
+    }
+}
+
+/// One simulated request on `network`
+async fn simulate_request(i: u32, network: &str) {
+    commands::STATUS.record_request();
+
+    match i {
+        1..=3 => info!("Processing request #{}", i),
+        4 => warn!("High load detected, request #{}", i),
+        5 => {
+            // Simulate logging with sensitive data
+            let fake_key = "0x1234567890123456789012345678901234567890123456789012345678901234";
+            info!(
+                "Payment from address {} using key {}",
+                "0x2C3E6F8A9B1234567890ABCDEF1234567890ABCD", fake_key
+            );
+            commands::STATUS.record_settlement(format!("request #5 on {}", network));
+        }
+        6 => {
+            error!("Failed to connect to RPC endpoint");
+            commands::STATUS.set_provider_health(network, false);
+            commands::STATUS.record_settlement_failure();
+        }
+        7..=9 => {
+            info!("Recovered, processing request #{}", i);
+            commands::STATUS.set_provider_health(network, true);
+        }
+        10 => info!("Shutting down gracefully..."),
+        _ => {}
     }
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize logging (console + IRC if enabled)
-    init_tracing();
+    let grace = env::var("SHUTDOWN_GRACE_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(shutdown::DEFAULT_GRACE);
+    let mut shutdown = Shutdown::new(grace);
+
+    // Initialize logging (console + IRC/OpenTelemetry if enabled)
+    let (otel_provider, journal) = init_tracing(&mut shutdown);
 
     info!("Starting IRC logging proof of concept...");
 
-    // Simulate some application activity
-    for i in 1..=10 {
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+    // Optional heartbeat (disabled by default so quiet deployments stay quiet)
+    if let Some(secs) = env::var("IRC_HEARTBEAT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+    {
+        let heartbeat = tokio::spawn(heartbeat_task(Duration::from_secs(secs), shutdown.token()));
+        shutdown.register("heartbeat", heartbeat);
+    }
+
+    // Status answered by the !supported / !health / !status bot commands
+    commands::STATUS.set_supported(vec!["base".to_string(), "avalanche".to_string()]);
+    commands::STATUS.set_provider_health("base", true);
+    commands::STATUS.set_provider_health("avalanche", true);
+
+    // Run until the activity completes or SIGTERM/Ctrl+C arrives
+    tokio::select! {
+        _ = simulate_activity() => {}
+        _ = shutdown::signal() => info!("Shutdown signal received, shutting down..."),
+    }
+    info!("Proof of concept complete!");
+
+    // Flush the IRC queue and QUIT, bounded by the grace period
+    if !shutdown.run().await {
+        eprintln!("Shutdown did not complete within {:?}", grace);
+    }
+
+    // The journal lives on in the global subscriber, so wait for its writer
+    // rather than relying on drop
+    if let Some(journal) = journal {
+        journal.sync();
+    }
+
+    // Flush any spans still buffered by the batch exporter
+    if let Some(provider) = otel_provider {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Failed to shut down OpenTelemetry: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(template: &str) -> IrcPrefix {
+        IrcPrefix {
+            template: template.to_string(),
+            host: "facilitator-1".to_string(),
+        }
+    }
+
+    #[test]
+    fn default_prefix_matches_legacy_format() {
+        let rendered = prefix(IrcPrefix::DEFAULT).render(&tracing::Level::WARN, "x402", None);
+        assert_eq!(
+            format_irc_line(&rendered, "High load", MAX_IRC_LEN),
+            "[WARN] High load"
+        );
+    }
+
+    #[test]
+    fn prefix_renders_each_placeholder() {
+        let at = UNIX_EPOCH + Duration::from_secs(13 * 3600 + 5 * 60 + 9);
+        let render = |template| {
+            let rendered =
+                prefix(template).render(&tracing::Level::ERROR, "x402_rs::chain", Some("base"));
+            stamp_line(&rendered, at, Timestamps::Prefix)
+        };
 
-        match i {
-            1..=3 => info!("Processing request #{}", i),
-            4 => warn!("High load detected, request #{}", i),
-            5 => {
-                // Simulate logging with sensitive data
-                let fake_key = "0x1234567890123456789012345678901234567890123456789012345678901234";
-                info!(
-                    "Payment from address {} using key {}",
-                    "0x2C3E6F8A9B1234567890ABCDEF1234567890ABCD", fake_key
-                );
+        assert_eq!(render("{level}"), "ERROR");
+        assert_eq!(render("{target}"), "x402_rs::chain");
+        assert_eq!(render("{host}"), "facilitator-1");
+        assert_eq!(render("{ts}"), "13:05:09");
+        assert_eq!(render("{network}"), "base");
+        assert_eq!(
+            render("{ts} {host} [{level}] {target}:"),
+            "13:05:09 facilitator-1 [ERROR] x402_rs::chain:"
+        );
+    }
+
+    #[test]
+    fn truncation_keeps_prefix_within_limit() {
+        let rendered = "facilitator-1 [INFO]";
+        let line = format_irc_line(rendered, &"a".repeat(1000), MAX_IRC_LEN);
+        assert!(line.starts_with("facilitator-1 [INFO] aaa"));
+        assert_eq!(line.len(), MAX_IRC_LEN + "... [truncated]".len());
+    }
+
+    #[test]
+    fn timestamped_lines_stay_within_limit() {
+        let at = UNIX_EPOCH + Duration::from_secs(86_400 + 7 * 3600 + 8 * 60 + 30);
+        let limit = |timestamps: Timestamps| MAX_IRC_LEN - timestamps.width();
+
+        let line = format_irc_line("[INFO]", &"a".repeat(1000), limit(Timestamps::Prepend));
+        let delivered = stamp_line(&line, at, Timestamps::Prepend);
+        assert!(delivered.starts_with("07:08:30 [INFO] aaa"));
+        assert_eq!(delivered.len(), MAX_IRC_LEN + "... [truncated]".len());
+
+        let line = format_irc_line("{ts} [INFO]", &"a".repeat(1000), limit(Timestamps::Prefix));
+        let delivered = stamp_line(&line, at, Timestamps::Prefix);
+        assert!(delivered.starts_with("07:08:30 [INFO] aaa"));
+        assert_eq!(delivered.len(), MAX_IRC_LEN + "... [truncated]".len());
+
+        assert_eq!(stamp_line("[INFO] x", at, Timestamps::Off), "[INFO] x");
+    }
+
+    #[test]
+    fn summaries_are_stamped_in_both_modes() {
+        let at = UNIX_EPOCH + Duration::from_secs(7 * 3600 + 8 * 60 + 30);
+        let summary = "(last message repeated 3 times)";
+        for timestamps in [Timestamps::Prepend, Timestamps::Prefix] {
+            assert_eq!(
+                stamp_summary(summary, at, timestamps),
+                "07:08:30 (last message repeated 3 times)"
+            );
+        }
+        assert_eq!(stamp_summary(summary, at, Timestamps::Off), summary);
+    }
+
+    #[test]
+    fn truncation_respects_char_boundaries() {
+        let line = truncate_irc_message(&"é".repeat(300), 5);
+        assert_eq!(line, "éé... [truncated]");
+    }
+
+    /// Lines `IrcLayer` queues for the events `emit` logs
+    fn forwarded_lines(template: &str, filter: Option<&str>, emit: impl FnOnce()) -> Vec<String> {
+        let prefix = prefix(template);
+        let timestamps = if prefix.uses_ts() {
+            Timestamps::Prefix
+        } else {
+            Timestamps::Off
+        };
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let layer = IrcLayer {
+            tx,
+            journal: None,
+            prefix,
+            timestamps,
+            enqueue: Mutex::new(()),
+            network_filter: filter.map(|f| f.split(',').map(str::to_string).collect()),
+        };
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), emit);
+
+        let mut lines = Vec::new();
+        while let Ok(line) = rx.try_recv() {
+            lines.push(line.text);
+        }
+        lines
+    }
+
+    #[test]
+    fn network_is_taken_from_enclosing_span_or_event() {
+        let lines = forwarded_lines("[{level}] {network}", None, || {
+            let span = info_span!("verify", network = "base");
+            let _entered = span.enter();
+            info!("inside span");
+            info!(network = "avalanche", "event field wins");
+        });
+        assert_eq!(
+            lines,
+            vec!["[INFO] base inside span", "[INFO] avalanche event field wins"]
+        );
+    }
+
+    #[test]
+    fn network_filter_drops_other_networks() {
+        let lines = forwarded_lines("[{level}] {network}", Some("base"), || {
+            info_span!("settle", network = "avalanche").in_scope(|| info!("avalanche log"));
+            info_span!("settle", network = "base").in_scope(|| info!("base log"));
+            info!("untagged log");
+        });
+        assert_eq!(lines, vec!["[INFO] base base log", "[INFO] untagged log"]);
+    }
+
+    #[test]
+    fn ts_placeholder_is_filled_on_delivery_so_repeats_dedup() {
+        let texts = forwarded_lines("{ts} [{level}]", None, || {
+            error!("rpc down");
+            error!("rpc down");
+        });
+        assert_eq!(texts, vec!["{ts} [ERROR] rpc down"; 2]);
+
+        // Repeats with different event times still collapse; the line that
+        // goes out shows its own time
+        let t0 = UNIX_EPOCH + Duration::from_secs(7 * 3600 + 8 * 60 + 30);
+        let now = Instant::now();
+        let mut dedup = Deduplicator::new(Duration::from_secs(10));
+        let mut delivered = Vec::new();
+        for secs in 0..3 {
+            let line = QueuedLine {
+                journal_id: None,
+                at: t0 + Duration::from_secs(secs),
+                text: texts[0].clone(),
+            };
+            for text in dedup.push(&line.text, now + Duration::from_secs(secs)) {
+                delivered.push(stamp_line(&text, line.at, Timestamps::Prefix));
             }
-            6 => error!("Failed to connect to RPC endpoint"),
-            7..=9 => info!("Recovered, processing request #{}", i),
-            10 => info!("Shutting down gracefully..."),
-            _ => {}
         }
+        assert_eq!(delivered, vec!["07:08:30 [ERROR] rpc down"]);
+        assert_eq!(
+            dedup.finish().as_deref(),
+            Some("(last message repeated 2 times)")
+        );
     }
 
-    // Give time for queued IRC messages to send
-    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-    info!("Proof of concept complete!");
+    #[test]
+    fn empty_network_filter_means_no_filter() {
+        assert_eq!(parse_network_filter(""), None);
+        assert_eq!(parse_network_filter(" , "), None);
+        assert_eq!(
+            parse_network_filter("Base, avalanche"),
+            Some(vec!["base".to_string(), "avalanche".to_string()])
+        );
+    }
+
+    #[test]
+    fn opentelemetry_internal_logs_are_not_forwarded() {
+        let lines = forwarded_lines("[{level}] {network}", None, || {
+            error!(target: "opentelemetry_sdk", "BatchSpanProcessor.ExportError");
+            error!(target: "opentelemetry-otlp", "HttpClient.ExportFailed");
+            error!("Failed to connect to RPC endpoint");
+        });
+        assert_eq!(lines, vec!["[ERROR] Failed to connect to RPC endpoint"]);
+    }
+
+    /// One-connection IRC server on loopback. Every line received is passed to
+    /// `seen`. A responsive server answers PINGs and closes the connection on
+    /// QUIT; an unresponsive one just reads.
+    async fn fake_irc_server(
+        listener: tokio::net::TcpListener,
+        responsive: bool,
+        seen: mpsc::UnboundedSender<String>,
+    ) {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let (socket, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let _ = seen.send(line.clone());
+            if !responsive {
+                continue;
+            }
+            if let Some(token) = line.strip_prefix("PING ") {
+                let pong = format!(":fake PONG fake {}\r\n", token);
+                writer.write_all(pong.as_bytes()).await.unwrap();
+            } else if line.starts_with("QUIT") {
+                writer.write_all(b"ERROR :Closing link\r\n").await.unwrap();
+                return;
+            }
+        }
+    }
+
+    /// Connect an `irc_sender_task` to a fake server, with an optional journal
+    async fn start_sender(
+        responsive: bool,
+        journal: Option<Arc<Journal>>,
+        shutdown: &mut Shutdown,
+    ) -> (
+        mpsc::UnboundedSender<QueuedLine>,
+        mpsc::UnboundedReceiver<String>,
+    ) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (seen_tx, seen) = mpsc::unbounded_channel();
+        tokio::spawn(fake_irc_server(listener, responsive, seen_tx));
+
+        let options = IrcSenderOptions {
+            channel: "#logs".to_string(),
+            config: Config {
+                nickname: Some("poc".to_string()),
+                server: Some("127.0.0.1".to_string()),
+                port: Some(port),
+                use_tls: Some(false),
+                ..Default::default()
+            },
+            admins: Vec::new(),
+            dedup_window: Duration::ZERO,
+            timestamps: Timestamps::Off,
+        };
+        let (tx, rx) = mpsc::unbounded_channel();
+        let sender = tokio::spawn(irc_sender_task(rx, options, journal, shutdown.sink_token()));
+        shutdown.register_sink("irc sender", sender);
+        (tx, seen)
+    }
+
+    /// Wait until the fake server has received `line`
+    async fn wait_for_line(seen: &mut mpsc::UnboundedReceiver<String>, line: &str) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while seen.recv().await.unwrap() != line {}
+        })
+        .await
+        .unwrap_or_else(|_| panic!("server never received {:?}", line));
+    }
+
+    fn journaled_line(journal: &Journal, text: &str) -> QueuedLine {
+        let at = SystemTime::now();
+        QueuedLine {
+            journal_id: Some(journal.record(text, at)),
+            at,
+            text: text.to_string(),
+        }
+    }
+
+    fn temp_journal(name: &str) -> (std::path::PathBuf, Arc<Journal>) {
+        let path = env::temp_dir().join(format!("irc-sender-{}-{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (journal, _) = Journal::open(&path, journal::DEFAULT_MAX_BYTES).unwrap();
+        (path, Arc::new(journal))
+    }
+
+    #[tokio::test]
+    async fn lines_are_marked_delivered_once_the_server_acknowledges_them() {
+        let (path, journal) = temp_journal("ack");
+        let mut shutdown = Shutdown::new(Duration::from_secs(5));
+        let (tx, mut seen) = start_sender(true, Some(journal.clone()), &mut shutdown).await;
+
+        tx.send(journaled_line(&journal, "[WARN] disk low")).unwrap();
+        wait_for_line(&mut seen, "PRIVMSG #logs :[WARN] disk low").await;
+        wait_for_line(&mut seen, "PING ack-0").await;
+
+        // The D record is written once the PONG has been read
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !std::fs::read_to_string(&path).unwrap().contains("D\t0") {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("line was never marked delivered");
+
+        shutdown.run().await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_lines_stay_in_the_journal() {
+        let (path, journal) = temp_journal("unacked");
+        let mut shutdown = Shutdown::new(Duration::from_millis(300));
+        let (tx, mut seen) = start_sender(false, Some(journal.clone()), &mut shutdown).await;
+
+        tx.send(journaled_line(&journal, "[ERROR] rpc down")).unwrap();
+        wait_for_line(&mut seen, "PRIVMSG #logs :[ERROR] rpc down").await;
+        shutdown.run().await;
+        drop(journal);
+
+        let (_, replay) = Journal::open(&path, journal::DEFAULT_MAX_BYTES).unwrap();
+        assert_eq!(replay.len(), 1);
+        assert_eq!(replay[0].text, "[ERROR] rpc down");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn json_console_writes_one_object_per_line() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let writer = buffer.clone();
+        let layer = json_console_layer(move || SharedBuffer(writer.clone()));
+        tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            info_span!("request", network = "base").in_scope(|| {
+                info!("Processing request #1");
+                warn!("High load\ndetected");
+            });
+        });
+
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("every line is JSON"))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "Processing request #1");
+        assert_eq!(lines[0]["span"]["network"], "base");
+        assert_eq!(lines[1]["message"], "High load\ndetected");
+    }
+
+    /// `MakeWriter` target collecting output in memory
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_queued_lines_and_quit_to_the_server() {
+        let (path, journal) = temp_journal("quit");
+        let mut shutdown = Shutdown::new(Duration::from_secs(5));
+        let (tx, mut seen) = start_sender(true, Some(journal.clone()), &mut shutdown).await;
+        wait_for_line(&mut seen, "PRIVMSG #logs :IRC logging initialized").await;
+
+        // Still queued when shutdown starts
+        tx.send(journaled_line(&journal, "[INFO] Proof of concept complete!"))
+            .unwrap();
+        tx.send(journaled_line(&journal, "[INFO] bye")).unwrap();
+        assert!(shutdown.run().await);
+
+        let mut received = Vec::new();
+        while let Ok(line) = seen.try_recv() {
+            received.push(line);
+        }
+        let privmsgs: Vec<_> = received
+            .iter()
+            .filter(|line| line.starts_with("PRIVMSG"))
+            .collect();
+        assert_eq!(
+            privmsgs,
+            vec![
+                "PRIVMSG #logs :[INFO] Proof of concept complete!",
+                "PRIVMSG #logs :[INFO] bye"
+            ]
+        );
+        assert_eq!(received.last().unwrap(), "QUIT :Shutting down");
+
+        // Confirmed by the server closing after QUIT, so nothing to replay
+        drop(journal);
+        let (_, replay) = Journal::open(&path, journal::DEFAULT_MAX_BYTES).unwrap();
+        assert!(replay.is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn lines_logged_by_finishing_work_reach_the_server_before_quit() {
+        use tracing::instrument::WithSubscriber;
+
+        let mut shutdown = Shutdown::new(Duration::from_secs(5));
+        let (tx, mut seen) = start_sender(true, None, &mut shutdown).await;
+        wait_for_line(&mut seen, "PRIVMSG #logs :IRC logging initialized").await;
+
+        // An in-flight settle that only logs its result after shutdown starts
+        let layer = IrcLayer {
+            tx,
+            journal: None,
+            prefix: prefix(IrcPrefix::DEFAULT),
+            timestamps: Timestamps::Off,
+            enqueue: Mutex::new(()),
+            network_filter: None,
+        };
+        let token = shutdown.token();
+        let worker = async move {
+            token.cancelled().await;
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            info!("Settlement confirmed");
+        };
+        shutdown.register(
+            "settle worker",
+            tokio::spawn(worker.with_subscriber(tracing_subscriber::registry().with(layer))),
+        );
+        assert!(shutdown.run().await);
+
+        let mut received = Vec::new();
+        while let Ok(line) = seen.try_recv() {
+            received.push(line);
+        }
+        let settled = received
+            .iter()
+            .position(|line| line == "PRIVMSG #logs :[INFO] Settlement confirmed")
+            .expect("the worker's line never reached the server");
+        let quit = received
+            .iter()
+            .position(|line| line.starts_with("QUIT"))
+            .unwrap();
+        assert!(settled < quit);
+    }
 }
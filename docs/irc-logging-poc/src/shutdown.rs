@@ -0,0 +1,179 @@
+//! Coordinated shutdown
+//!
+//! Shutdown runs in two phases within a single grace period. Application tasks
+//! (in a full deployment the HTTP server and settle worker) watch `token()`;
+//! it is cancelled first and they are awaited so in-flight work can finish and
+//! log its last lines. Only then is `sink_token()` cancelled, which the log
+//! sinks (the IRC sender) watch to flush their queue and disconnect.
+
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Default grace period for `SHUTDOWN_GRACE_SECS`
+pub const DEFAULT_GRACE: Duration = Duration::from_secs(10);
+
+pub struct Shutdown {
+    token: CancellationToken,
+    sink_token: CancellationToken,
+    grace: Duration,
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+    sinks: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Shutdown {
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            token: CancellationToken::new(),
+            sink_token: CancellationToken::new(),
+            grace,
+            tasks: Vec::new(),
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Token that application tasks watch to start their shutdown
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Token that log sinks watch; cancelled once application tasks are done
+    pub fn sink_token(&self) -> CancellationToken {
+        self.sink_token.clone()
+    }
+
+    /// Register an application task to wait for on shutdown
+    pub fn register(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.tasks.push((name, handle));
+    }
+
+    /// Register a log sink, stopped only after every application task
+    pub fn register_sink(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.sinks.push((name, handle));
+    }
+
+    /// Cancel the application token and wait for those tasks, then cancel the
+    /// sink token and wait for the sinks. Tasks still running when the grace
+    /// period ends are aborted. Returns whether all tasks finished in time.
+    pub async fn run(self) -> bool {
+        let deadline = tokio::time::Instant::now() + self.grace;
+
+        self.token.cancel();
+        let mut clean = wait_all(self.tasks, deadline).await;
+
+        self.sink_token.cancel();
+        clean &= wait_all(self.sinks, deadline).await;
+        clean
+    }
+}
+
+/// Await tasks in order until `deadline`, aborting any still running then
+async fn wait_all(
+    tasks: Vec<(&'static str, JoinHandle<()>)>,
+    deadline: tokio::time::Instant,
+) -> bool {
+    let mut clean = true;
+    for (name, mut handle) in tasks {
+        if tokio::time::timeout_at(deadline, &mut handle)
+            .await
+            .is_err()
+        {
+            eprintln!("Shutdown grace period elapsed, aborting {}", name);
+            handle.abort();
+            clean = false;
+        }
+    }
+    clean
+}
+
+/// Resolves on Ctrl+C or (on Unix) SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn in_flight_work_completes_before_exit() {
+        let mut shutdown = Shutdown::new(Duration::from_millis(500));
+        let finished = Arc::new(AtomicBool::new(false));
+
+        let token = shutdown.token();
+        let done = finished.clone();
+        shutdown.register(
+            "worker",
+            tokio::spawn(async move {
+                token.cancelled().await;
+                // Finish the in-flight request after shutdown starts
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                done.store(true, Ordering::SeqCst);
+            }),
+        );
+
+        assert!(shutdown.run().await);
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn sinks_stop_only_after_application_tasks() {
+        let mut shutdown = Shutdown::new(Duration::from_millis(500));
+        let worker_done = Arc::new(AtomicBool::new(false));
+
+        let token = shutdown.token();
+        let done = worker_done.clone();
+        shutdown.register(
+            "worker",
+            tokio::spawn(async move {
+                token.cancelled().await;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                done.store(true, Ordering::SeqCst);
+            }),
+        );
+
+        let sink_token = shutdown.sink_token();
+        let done = worker_done.clone();
+        let sink = tokio::spawn(async move {
+            sink_token.cancelled().await;
+            assert!(
+                done.load(Ordering::SeqCst),
+                "sink stopped before the worker"
+            );
+        });
+        shutdown.register_sink("sink", sink);
+
+        assert!(shutdown.run().await);
+    }
+
+    #[tokio::test]
+    async fn tasks_ignoring_cancellation_are_aborted_after_grace() {
+        let mut shutdown = Shutdown::new(Duration::from_millis(50));
+        shutdown.register("stuck", tokio::spawn(std::future::pending::<()>()));
+
+        let started = tokio::time::Instant::now();
+        assert!(!shutdown.run().await);
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}